@@ -7,5 +7,15 @@ use clap::{command, Parser};
 pub struct Config {
 
     #[arg(short, long)]
-    pub input_dir: String
+    pub input_dir: String,
+
+    /// Render using only embedded fonts, ignoring host system fonts, for
+    /// reproducible output across machines.
+    #[arg(long)]
+    pub embedded_fonts: bool,
+
+    /// Path to a declarative font manifest (`fonts.json` / `fonts.toml`). When
+    /// set it fully governs the family and fallback order Typst sees.
+    #[arg(long)]
+    pub fonts: Option<PathBuf>,
 }
\ No newline at end of file
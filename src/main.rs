@@ -1,7 +1,7 @@
 use actix_web::{App, HttpServer};
 use clap::Parser;
 use config::Config;
-use models::Model;
+use models::{FontManifest, FontProvider, Model};
 use server::start_server;
 
 mod config;
@@ -12,7 +12,12 @@ mod server;
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let config = Config::parse();
-    let model = Model::read(&config.input_dir).unwrap();
+    let mut fonts = FontProvider::new(config.embedded_fonts);
+    if let Some(manifest_path) = &config.fonts {
+        let manifest = FontManifest::load(manifest_path).unwrap();
+        fonts = fonts.with_manifest(manifest);
+    }
+    let model = Model::read(&config.input_dir, fonts).unwrap();
 
     //println!("{:?}", model);
 
@@ -1,42 +1,573 @@
 use std::fs::{self, DirEntry};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+use memmap2::Mmap;
+use siphasher::sip::SipHasher13;
 use convert_case::{Case, Casing};
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use typst::{
     diag::{FileError, FileResult, SourceResult},
-    foundations::{Bytes, Datetime, Dict, Module, Value},
+    foundations::{Bytes, Datetime, Dict, Module, Smart, Value},
     syntax::{FileId, Source, Span, VirtualPath},
-    text::{Font, FontBook, FontInfo},
+    text::{Font, FontBook, FontInfo, FontStyle, FontWeight},
+    visualize::Color,
     Library, World,
 };
 use anyhow::{Context, Result};
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine as _;
 use system_fonts::SystemFonts;
+use rust_embed::RustEmbed;
 use std::collections::HashMap;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// Fonts embedded into the binary at compile time.
+#[derive(RustEmbed)]
+#[folder = "assets/fonts/"]
+#[include = "*.ttf"]
+#[include = "*.otf"]
+#[include = "*.ttc"]
+#[exclude = ".DS_Store"]
+struct FontAssets;
+
+/// Slant of a manifest face, checked against the face's real style at load time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FontSlant {
+    #[default]
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl FontSlant {
+    fn to_typst(self) -> FontStyle {
+        match self {
+            FontSlant::Normal => FontStyle::Normal,
+            FontSlant::Italic => FontStyle::Italic,
+            FontSlant::Oblique => FontStyle::Oblique,
+        }
+    }
+}
+
+fn default_weight() -> u16 {
+    400
+}
+
+/// A single face within a family, checked against its real `FontInfo` on load.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FontEntry {
+    pub asset: String,
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+    #[serde(default)]
+    pub slant: FontSlant,
+    #[serde(default)]
+    pub language: Vec<String>,
+}
+
+/// A named family with its ordered faces.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FamilyEntry {
+    pub family: String,
+    pub fonts: Vec<FontEntry>,
+}
+
+/// Declarative font configuration; `fallback` names the family pushed last.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FontManifest {
+    pub families: Vec<FamilyEntry>,
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+impl FontManifest {
+    /// Load a manifest from a `.json` or `.toml` file, dispatching on its extension.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("reading font manifest {}", path.display()))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text).context("parsing font manifest (toml)"),
+            _ => serde_json::from_str(&text).context("parsing font manifest (json)"),
+        }
+    }
+}
+
+/// A compact set of codepoints, used for corpus and per-face coverage.
+#[derive(Debug, Clone, Default)]
+pub struct CharSet {
+    chars: BTreeSet<char>,
+}
+
+impl CharSet {
+    fn insert(&mut self, c: char) {
+        self.chars.insert(c);
+    }
+
+    fn extend_from_str(&mut self, s: &str) {
+        self.chars.extend(s.chars());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars.iter().copied()
+    }
+}
+
+/// Backing storage for a face that is only turned into a `Font` on demand.
+enum FontSource {
+    /// Bytes already held in memory (embedded assets, DejaVu, manifest assets).
+    Embedded(Bytes),
+    /// A memory-mapped file on disk, materialized lazily.
+    Mapped(Arc<Mmap>),
+}
+
+impl FontSource {
+    fn bytes(&self) -> Bytes {
+        match self {
+            FontSource::Embedded(bytes) => bytes.clone(),
+            FontSource::Mapped(mmap) => mmap[..].to_vec().into(),
+        }
+    }
+}
+
+/// A face known to the [`FontBook`], materialized into a `Font` on first use.
+struct FontSlot {
+    source: FontSource,
+    index: u32,
+    /// Corpus codepoints this face covers; empty when not computed.
+    coverage: CharSet,
+    font: OnceLock<Option<Font>>,
+}
+
+impl FontSlot {
+    fn get(&self) -> Option<Font> {
+        self.font
+            .get_or_init(|| Font::new(self.source.bytes(), self.index as usize))
+            .clone()
+    }
+}
+
+/// Supplies faces for the Typst [`FontBook`], either from a [`FontManifest`]
+/// or by auto-discovering embedded and system fonts.
+#[derive(Clone)]
+pub struct FontProvider {
+    embedded_only: bool,
+    manifest: Option<Arc<FontManifest>>,
+    /// Corpus codepoints; faces covering none of it are dropped unless force-kept
+    /// (manifest faces and the DejaVu fallback).
+    corpus: Option<Arc<CharSet>>,
+    /// Stored at most once and shared by every `FontProvider` clone.
+    book: Arc<OnceLock<Arc<(FontBook, Vec<FontSlot>)>>>,
+}
+
+impl std::fmt::Debug for FontProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FontProvider")
+            .field("embedded_only", &self.embedded_only)
+            .field("manifest", &self.manifest)
+            .field("corpus", &self.corpus)
+            .finish()
+    }
+}
+
+impl FontProvider {
+    pub fn new(embedded_only: bool) -> Self {
+        Self { embedded_only, manifest: None, corpus: None, book: Arc::new(OnceLock::new()) }
+    }
+
+    /// Attach a declarative manifest; when set it fully governs the book.
+    pub fn with_manifest(mut self, manifest: FontManifest) -> Self {
+        self.manifest = Some(Arc::new(manifest));
+        self
+    }
+
+    /// Attach the corpus codepoint set so coverage-driven pruning can run.
+    pub fn with_corpus(mut self, corpus: CharSet) -> Self {
+        self.corpus = Some(Arc::new(corpus));
+        self
+    }
+
+    /// The font book and its lazy face slots, built once and cached.
+    fn book(&self) -> Result<Arc<(FontBook, Vec<FontSlot>)>> {
+        if let Some(book) = self.book.get() {
+            return Ok(book.clone());
+        }
+        let built = Arc::new(self.build()?);
+        let _ = self.book.set(built.clone());
+        Ok(self.book.get().cloned().unwrap_or(built))
+    }
+
+    /// A stable fingerprint of the font configuration, used in the [`RenderCache`] key.
+    fn version(&self) -> u64 {
+        let mut hasher = SipHasher13::new();
+        self.embedded_only.hash(&mut hasher);
+        match &self.manifest {
+            Some(manifest) => {
+                for family in &manifest.families {
+                    family.family.hash(&mut hasher);
+                    for entry in &family.fonts {
+                        entry.asset.hash(&mut hasher);
+                        entry.index.hash(&mut hasher);
+                        entry.weight.hash(&mut hasher);
+                        entry.slant.hash(&mut hasher);
+                        entry.language.hash(&mut hasher);
+                    }
+                }
+                manifest.fallback.hash(&mut hasher);
+            }
+            None => "system".hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    /// Whether a face restricted to `languages` is worth keeping for the corpus.
+    /// Empty list, unrecognized tags, or no corpus all default to keeping it.
+    fn language_matches(&self, languages: &[String]) -> bool {
+        if languages.is_empty() {
+            return true;
+        }
+        let Some(corpus) = &self.corpus else { return true };
+
+        for lang in languages {
+            let ranges: &[(char, char)] = match lang.to_lowercase().as_str() {
+                "zh" | "ja" | "ko" => &[('\u{4E00}', '\u{9FFF}'), ('\u{3040}', '\u{30FF}')],
+                "ar" => &[('\u{0600}', '\u{06FF}')],
+                "he" => &[('\u{0590}', '\u{05FF}')],
+                _ => return true,
+            };
+            if corpus.iter().any(|c| ranges.iter().any(|(lo, hi)| c >= *lo && c <= *hi)) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Read bytes for a manifest asset, preferring an embedded face.
+    fn asset_bytes(asset: &str) -> Result<Bytes> {
+        if let Some(file) = FontAssets::get(asset) {
+            return Ok(file.data.to_vec().into());
+        }
+        let data = fs::read(asset)
+            .with_context(|| format!("loading font asset {}", asset))?;
+        Ok(data.into())
+    }
+
+    /// Push a candidate face as a lazy [`FontSlot`], skipping it if a known
+    /// corpus finds no coverage, unless `force_keep` is set.
+    fn push_face(
+        &self,
+        book: &mut FontBook,
+        slots: &mut Vec<FontSlot>,
+        source: FontSource,
+        index: u32,
+        force_keep: bool,
+    ) {
+        let Some(font) = Font::new(source.bytes(), index as usize) else { return };
+        let Some(info) = FontInfo::new(&font) else { return };
+
+        let mut coverage = CharSet::default();
+        if let Some(corpus) = &self.corpus {
+            let face = font.ttf();
+            for ch in corpus.iter() {
+                if face.glyph_index(ch).is_some() {
+                    coverage.insert(ch);
+                }
+            }
+            if !force_keep && coverage.is_empty() {
+                return;
+            }
+        }
+
+        book.push(info);
+        slots.push(FontSlot { source, index, coverage, font: OnceLock::new() });
+    }
+
+    /// Build the font book together with the slot vector `World::font` indexes.
+    fn build(&self) -> Result<(FontBook, Vec<FontSlot>)> {
+        if let Some(manifest) = &self.manifest {
+            return self.build_from_manifest(manifest);
+        }
+
+        let mut slots = Vec::new();
+        let mut font_book = FontBook::new();
+
+        // Embedded faces first so they take precedence.
+        for file in FontAssets::iter() {
+            let Some(asset) = FontAssets::get(&file) else { continue };
+            let bytes: Bytes = asset.data.to_vec().into();
+            let count = Font::iter(bytes.clone()).count();
+            for index in 0..count as u32 {
+                self.push_face(&mut font_book, &mut slots, FontSource::Embedded(bytes.clone()), index, false);
+            }
+        }
+
+        // Merge in every system face unless the caller asked for embedded-only.
+        if !self.embedded_only {
+            if let Ok(system_fonts) = system_fonts::SystemFonts::new() {
+                if let Some(fonts_by_family) = system_fonts.db().all_fonts() {
+                    for (_, font_paths) in fonts_by_family.iter() {
+                        for path in font_paths {
+                            let Ok(file) = std::fs::File::open(path) else { continue };
+                            // SAFETY: font files are read-only assets; a
+                            // concurrent external truncation is not expected.
+                            let Ok(mmap) = (unsafe { Mmap::map(&file) }) else { continue };
+                            let mmap = Arc::new(mmap);
+                            let count = Font::iter((mmap[..].to_vec()).into()).count();
+                            for index in 0..count as u32 {
+                                self.push_face(&mut font_book, &mut slots, FontSource::Mapped(mmap.clone()), index, false);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Always append the embedded DejaVu as a guaranteed-present fallback.
+        let dejavu: Bytes = include_bytes!("../assets/DejaVuSans.ttf").to_vec().into();
+        self.push_face(&mut font_book, &mut slots, FontSource::Embedded(dejavu), 0, true);
+
+        if slots.is_empty() {
+            return Err(anyhow::anyhow!("No fonts available"));
+        }
+
+        self.warn_uncovered(&slots);
+        Ok((font_book, slots))
+    }
+
+    /// Build the book strictly from the manifest, in declared order with the
+    /// `fallback` family moved last, validating each entry against its real `FontInfo`.
+    fn build_from_manifest(&self, manifest: &FontManifest) -> Result<(FontBook, Vec<FontSlot>)> {
+        if let Some(fallback) = &manifest.fallback {
+            if !manifest.families.iter().any(|f| &f.family == fallback) {
+                return Err(anyhow::anyhow!(
+                    "font manifest fallback {:?} does not name a declared family",
+                    fallback,
+                ));
+            }
+        }
+
+        let is_fallback = |family: &FamilyEntry| {
+            manifest.fallback.as_deref() == Some(family.family.as_str())
+        };
+        let ordered_families = manifest.families.iter().filter(|f| !is_fallback(f))
+            .chain(manifest.families.iter().filter(|f| is_fallback(f)));
+
+        let mut slots = Vec::new();
+        let mut font_book = FontBook::new();
+
+        for family in ordered_families {
+            for entry in &family.fonts {
+                if !self.language_matches(&entry.language) {
+                    continue;
+                }
+
+                let bytes = Self::asset_bytes(&entry.asset)?;
+                let font = Font::new(bytes.clone(), entry.index as usize).ok_or_else(|| {
+                    anyhow::anyhow!("font asset {} has no face at index {}", entry.asset, entry.index)
+                })?;
+                let info = FontInfo::new(&font).ok_or_else(|| {
+                    anyhow::anyhow!("font asset {} face {} has no readable font info", entry.asset, entry.index)
+                })?;
+
+                if !info.family.eq_ignore_ascii_case(&family.family) {
+                    return Err(anyhow::anyhow!(
+                        "font asset {} is declared under family {:?} but its real family is {:?}",
+                        entry.asset, family.family, info.family,
+                    ));
+                }
+                if info.variant.weight.to_number() != entry.weight {
+                    return Err(anyhow::anyhow!(
+                        "font asset {} declares weight {} but its real weight is {}",
+                        entry.asset, entry.weight, info.variant.weight.to_number(),
+                    ));
+                }
+                if info.variant.style != entry.slant.to_typst() {
+                    return Err(anyhow::anyhow!(
+                        "font asset {} declares slant {:?} but its real style is {:?}",
+                        entry.asset, entry.slant, info.variant.style,
+                    ));
+                }
+
+                // Manifest faces that pass validation are always kept.
+                let before = slots.len();
+                self.push_face(&mut font_book, &mut slots, FontSource::Embedded(bytes), entry.index, true);
+                if slots.len() == before {
+                    return Err(anyhow::anyhow!(
+                        "font asset {} has no face at index {}",
+                        entry.asset, entry.index,
+                    ));
+                }
+            }
+        }
+
+        if slots.is_empty() {
+            return Err(anyhow::anyhow!("font manifest produced no usable faces"));
+        }
+
+        self.warn_uncovered(&slots);
+        Ok((font_book, slots))
+    }
+
+    /// Warn about corpus codepoints that no retained face can render.
+    fn warn_uncovered(&self, slots: &[FontSlot]) {
+        let Some(corpus) = &self.corpus else { return };
+        let uncovered: Vec<char> = corpus
+            .iter()
+            .filter(|&c| !slots.iter().any(|s| s.coverage.chars.contains(&c)))
+            // Whitespace and control codepoints never need a glyph.
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+            .collect();
+        if !uncovered.is_empty() {
+            eprintln!("Warning: {} corpus codepoint(s) have no covering face", uncovered.len());
+        }
+    }
+}
+
+/// The Typst standard library, built once and shared so comemo memoization can reuse compiles.
+fn shared_library() -> Arc<Library> {
+    static LIBRARY: OnceLock<Arc<Library>> = OnceLock::new();
+    LIBRARY.get_or_init(|| Arc::new(Library::builder().build())).clone()
+}
+
+/// One cached render: output bytes plus the source mtime it was produced from.
+#[derive(Debug, Clone)]
+struct CachedRender {
+    bytes: Vec<u8>,
+    content_type: &'static str,
+    mtime: Option<SystemTime>,
+}
+
+/// Content-hash-keyed cache of compiled renders, shared across worker threads
+/// and invalidated by comparing the source file's mtime at lookup time.
+#[derive(Debug, Clone, Default)]
+struct RenderCache {
+    entries: Arc<Mutex<HashMap<u64, CachedRender>>>,
+}
+
+impl RenderCache {
+    /// Hash id, format, long text and fonts version; `scale` only affects
+    /// rasterized output, so it's excluded from the key for other formats.
+    fn key(id: &str, format: RenderFormat, scale: f32, long: &str, fonts_version: u64) -> u64 {
+        let mut hasher = SipHasher13::new();
+        id.hash(&mut hasher);
+        std::mem::discriminant(&format).hash(&mut hasher);
+        if matches!(format, RenderFormat::Png) {
+            scale.to_bits().hash(&mut hasher);
+        }
+        long.hash(&mut hasher);
+        fonts_version.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64, mtime: Option<SystemTime>) -> Option<(Vec<u8>, &'static str)> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?;
+        if entry.mtime != mtime {
+            return None;
+        }
+        Some((entry.bytes.clone(), entry.content_type))
+    }
+
+    fn insert(&self, key: u64, bytes: Vec<u8>, content_type: &'static str, mtime: Option<SystemTime>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, CachedRender { bytes, content_type, mtime });
+    }
+}
+
+/// Render `node` in `format`, serving a cached artifact when still valid.
+fn render_cached(
+    root: &Path,
+    fonts: &FontProvider,
+    cache: &RenderCache,
+    node: &Node,
+    format: RenderFormat,
+    scale: f32,
+) -> Result<(Vec<u8>, &'static str)> {
+    let mtime = fs::metadata(&node.path).ok().and_then(|m| m.modified().ok());
+    let key = RenderCache::key(&node.id, format, scale, &node.long, fonts.version());
+    if let Some(cached) = cache.get(key, mtime) {
+        return Ok(cached);
+    }
+
+    let code = wrap_note(&node.long);
+    let (bytes, content_type) = match format {
+        RenderFormat::Svg => render_typst_to_svg(&code, root, fonts).map(|svg| (svg.into_bytes(), "image/svg+xml"))?,
+        RenderFormat::Pdf => render_typst_to_pdf(&code, root, fonts).map(|pdf| (pdf, "application/pdf"))?,
+        RenderFormat::Png => render_typst_to_png(&code, scale, root, fonts).map(|png| (png, "image/png"))?,
+    };
+
+    cache.insert(key, bytes.clone(), content_type, mtime);
+    Ok((bytes, content_type))
+}
 
 #[derive(Debug, Clone)]
 pub struct Model {
     nodes: Vec<Node>,
+    root: PathBuf,
+    fonts: FontProvider,
+    render_cache: RenderCache,
 }
 
 impl Model {
-    pub fn read(dir: &str) -> Result<Self, OpenModelError> {
-        let dir = fs::read_dir(dir)
-            .map_err(|e| OpenModelError::NotADir)?;
+    pub fn read(dir: &str, fonts: FontProvider) -> Result<Self, OpenModelError> {
+        let root = PathBuf::from(dir);
+        let entries: Vec<DirEntry> = fs::read_dir(dir)
+            .map_err(|e| OpenModelError::NotADir)?
+            .filter_map(|e| e.ok())
+            .collect();
+
+        // Scan the corpus up front so the font book can drop uncovering faces.
+        let mut corpus = CharSet::default();
+        for entry in &entries {
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                corpus.extend_from_str(&content);
+            }
+        }
+        let fonts = fonts.with_corpus(corpus);
+
+        // Build the font book once up front so the parallel prewarm below doesn't race it.
+        fonts.book().map_err(|_| OpenModelError::CantBuildFonts)?;
+
         let mut files = vec![];
         // todo: consider async
-        for file in dir {
-            if let Some(node) = Node::parse(file.ok()) {
+        for file in entries {
+            if let Some(node) = Node::parse(Some(file)) {
                 files.push(node);
             } else {
                 return Err(OpenModelError::CantParseNode);
             }
         }
+
+        // Rendering is expensive and parallelizable; prewarm the render cache here.
+        let render_cache = RenderCache::default();
+        files.par_iter_mut().for_each(|node| {
+            if !node.looks_like_typst() {
+                return;
+            }
+            match render_cached(&root, &fonts, &render_cache, node, RenderFormat::Svg, 1.0) {
+                Ok((bytes, _)) => node.long_text_svg = String::from_utf8(bytes).ok(),
+                Err(e) => eprintln!("Failed to render Typst to SVG: {}", e),
+            }
+        });
+
         Ok(Model {
             nodes: files,
+            root,
+            fonts,
+            render_cache,
         })
     }
 
@@ -44,81 +575,117 @@ impl Model {
         self.nodes.clone()
     }
 
+    /// Render a node's long text on demand; `None` when no node with `id` exists.
+    pub fn render_node(
+        &self,
+        id: &str,
+        format: RenderFormat,
+        scale: f32,
+    ) -> Option<Result<(Vec<u8>, &'static str)>> {
+        let node = self.nodes.iter().find(|n| n.id == id)?;
+        Some(render_cached(&self.root, &self.fonts, &self.render_cache, node, format, scale))
+    }
+
     /// Determine all references between short and long descriptions of notes (really expensive).
-    pub fn get_edges(&self) -> Vec<Vec<String>> {
+    /// Link text is snake-case normalized before matching against real node ids.
+    pub fn get_edges(&self) -> Vec<Edge> {
+        let ids: HashMap<String, String> = self.nodes.iter()
+            .map(|n| (normalize_id(&n.id), n.id.clone()))
+            .collect();
+
         let mut res = Vec::new();
         self.nodes.par_iter()
-            .map(|a| a
+            .map(|node| node
                 .get_connections()
-                .iter()
-                .map(|b| vec![a.id.to_string(), b.to_string()])
-                .collect::<Vec<Vec<String>>>())
+                .into_iter()
+                .filter_map(|(raw, kind)| {
+                    let to = ids.get(&normalize_id(&raw))?;
+                    Some(Edge { from: node.id.clone(), to: to.clone(), kind })
+                })
+                .collect::<Vec<Edge>>())
             .collect_into_vec(&mut res);
         res.concat()
     }
+
+    /// Edges pointing at `id` — the inverse of [`Model::get_edges`].
+    pub fn get_backlinks(&self, id: &str) -> Vec<Edge> {
+        self.get_edges().into_iter().filter(|e| e.to == id).collect()
+    }
+}
+
+/// Snake-cases a matched link target for comparison against node ids.
+fn normalize_id(s: &str) -> String {
+    s.trim().to_case(Case::Snake)
+}
+
+/// The markdown syntax a link to another note was written in.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// A bare `[id]` reference.
+    Ref,
+    /// A `[title](id)` link.
+    Titled,
+}
+
+/// A resolved link from one note to another.
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
 }
 
-// A simple world implementation for Typst that handles a single source file.
+// A world implementation for Typst rooted at a note directory. The main note
+// is compiled from memory while `#import`/`#include`/`image(..)` resolve
+// against files on disk under `root`.
 struct TypstWorld {
     library: Arc<Library>,
+    root: PathBuf,
     source: Source,
-    font_book: Arc<FontBook>,
-    fonts: Vec<Font>,
+    main_id: FileId,
+    // Built once per `FontProvider` and shared across every `TypstWorld`
+    // compiled from it; see `FontProvider::book`.
+    fonts: Arc<(FontBook, Vec<FontSlot>)>,
+    // Parsed sources and raw bytes of referenced files, loaded on first access.
+    sources: Mutex<HashMap<FileId, Source>>,
+    files: Mutex<HashMap<FileId, Bytes>>,
 }
 
 impl TypstWorld {
-    // Create a new Typst world with the given source code.
-    fn new(source: &str) -> Result<Self> {
-        let library = Arc::new(Library::builder().build());
+    // Create a new Typst world with the given source code, rooting file
+    // resolution at `root`.
+    fn new(source: &str, root: impl Into<PathBuf>, provider: &FontProvider) -> Result<Self> {
+        let library = shared_library();
         let source = Source::detached(source);
-        
-        let mut fonts = Vec::new();
-        let mut font_book = FontBook::new();
-        
-        // Try to load system fonts
-        if let Ok(system_fonts) = system_fonts::SystemFonts::new() {
-            if let Some(fonts_by_family) = system_fonts.db().all_fonts() {
-                for (_, font_paths) in fonts_by_family.iter() {
-                    if let Some(path) = font_paths.first() {
-                        if let Ok(data) = std::fs::read(path) {
-                            if let Ok(font) = Font::new(data.into(), 0) {
-                                if let Some(info) = FontInfo::new(&font) {
-                                    font_book.push(info);
-                                    fonts.push(font);
-                                    // Just use the first available font for simplicity
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        
-        // If no fonts were found, use a built-in font
-        if fonts.is_empty() {
-            // Use a simple built-in font as fallback
-            let font_data = include_bytes!("../assets/DejaVuSans.ttf").to_vec();
-            if let Ok(font) = Font::new(font_data.into(), 0) {
-                if let Some(info) = FontInfo::new(&font) {
-                    font_book.push(info);
-                    fonts.push(font);
-                }
-            }
-        }
-        
-        // If still no fonts, return an error
-        if fonts.is_empty() {
-            return Err(anyhow::anyhow!("No fonts available"));
-        }
-        
+        let main_id = source.id();
+        let root = root.into();
+
+        let fonts = provider.book()?;
+
         Ok(Self {
             library,
+            root,
             source,
-            font_book: Arc::new(font_book),
+            main_id,
             fonts,
+            sources: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
         })
     }
+
+    // Resolve a `FileId` to a path on disk under `root`. Packages are not
+    // supported, so any packaged id is reported as such.
+    fn resolve(&self, id: FileId) -> FileResult<PathBuf> {
+        if let Some(package) = id.package() {
+            return Err(FileError::Package(
+                typst::diag::PackageError::NotFound(package.clone()),
+            ));
+        }
+        id.vpath()
+            .resolve(&self.root)
+            .ok_or_else(|| FileError::NotFound(id.vpath().as_rootless_path().to_path_buf()))
+    }
 }
 
 impl World for TypstWorld {
@@ -127,23 +694,41 @@ impl World for TypstWorld {
     }
 
     fn book(&self) -> &FontBook {
-        &self.font_book
+        &self.fonts.0
     }
 
     fn main(&self) -> Source {
         self.source.clone()
     }
 
-    fn source(&self, _id: FileId) -> FileResult<Source> {
-        Ok(self.source.clone())
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main_id {
+            return Ok(self.source.clone());
+        }
+        if let Some(source) = self.sources.lock().unwrap().get(&id) {
+            return Ok(source.clone());
+        }
+        let path = self.resolve(id)?;
+        let text = fs::read_to_string(&path)
+            .map_err(|e| FileError::from_io(e, &path))?;
+        let source = Source::new(id, text);
+        self.sources.lock().unwrap().insert(id, source.clone());
+        Ok(source)
     }
 
-    fn file(&self, _id: FileId) -> FileResult<Bytes> {
-        Err(FileError::NotFound(PathBuf::new()))
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        if let Some(bytes) = self.files.lock().unwrap().get(&id) {
+            return Ok(bytes.clone());
+        }
+        let path = self.resolve(id)?;
+        let data = fs::read(&path).map_err(|e| FileError::from_io(e, &path))?;
+        let bytes: Bytes = data.into();
+        self.files.lock().unwrap().insert(id, bytes.clone());
+        Ok(bytes)
     }
 
     fn font(&self, index: usize) -> Option<Font> {
-        self.fonts.get(index).cloned()
+        self.fonts.1.get(index)?.get()
     }
 
     fn today(&self, _offset: Option<i64>) -> Option<Datetime> {
@@ -151,39 +736,102 @@ impl World for TypstWorld {
     }
 }
 
-// Renders Typst code to an SVG string.
-fn render_typst_to_svg(code: &str) -> Result<String> {
-    // Create a Typst world with our source code
-    let world = match TypstWorld::new(code) {
+/// Output formats the render endpoint can produce from a note's long text.
+#[derive(Debug, Clone, Copy)]
+pub enum RenderFormat {
+    Svg,
+    Pdf,
+    Png,
+}
+
+impl RenderFormat {
+    /// Parse the `format` query parameter; `None` for an unrecognized value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "svg" => Some(RenderFormat::Svg),
+            "pdf" => Some(RenderFormat::Pdf),
+            "png" => Some(RenderFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// Wraps a note's long text in a document with the standard page/text styling.
+fn wrap_note(long_trimmed: &str) -> String {
+    format!(
+        r"""
+        #set page(
+            width: 600pt,
+            margin: 20pt,
+            fill: rgb("transparent"),
+        )
+        #set text(
+            size: 12pt,
+            fill: rgb(0, 0, 0),
+        )
+        {}
+        """,
+        long_trimmed
+    )
+}
+
+// Compiles Typst code into a document, sharing the world setup across all
+// export backends (SVG/PDF/PNG).
+fn compile_typst(code: &str, root: &Path, provider: &FontProvider) -> Result<typst::model::Document> {
+    let world = match TypstWorld::new(code, root, provider) {
         Ok(world) => world,
         Err(e) => {
             eprintln!("Failed to create Typst world: {}", e);
             return Err(e);
         }
     };
-    
-    // Compile the document
-    let document = match typst::compile(&world) {
-        Ok((document, _warnings)) => document,
+
+    match typst::compile(&world) {
+        Ok((document, _warnings)) => Ok(document),
         Err(errors) => {
             eprintln!("Failed to compile Typst: {:?}", errors);
-            return Err(anyhow::anyhow!("Failed to compile Typst"));
+            Err(anyhow::anyhow!("Failed to compile Typst"))
         }
-    };
-    
+    }
+}
+
+// Renders Typst code to an SVG string.
+fn render_typst_to_svg(code: &str, root: &Path, provider: &FontProvider) -> Result<String> {
+    let document = compile_typst(code, root, provider)?;
+
     // Get the first page's frame
     let frame = document.pages.first()
         .ok_or_else(|| anyhow::anyhow!("No pages in document"))?;
-    
+
     // Render to SVG
     let svg = typst_svg::svg(frame);
-    
+
     // Make the SVG background transparent
     let svg = svg.replace("<rect", "<rect fill=\"none\"");
-    
+
     Ok(svg)
 }
 
+// Renders Typst code to a print-ready PDF byte buffer.
+fn render_typst_to_pdf(code: &str, root: &Path, provider: &FontProvider) -> Result<Vec<u8>> {
+    let document = compile_typst(code, root, provider)?;
+    typst_pdf::pdf(&document, Smart::Auto, None)
+        .map_err(|errors| anyhow::anyhow!("Failed to export PDF: {:?}", errors))
+}
+
+// Renders the first page of Typst code to a raster PNG at the given scale.
+fn render_typst_to_png(code: &str, scale: f32, root: &Path, provider: &FontProvider) -> Result<Vec<u8>> {
+    let document = compile_typst(code, root, provider)?;
+
+    let frame = document.pages.first()
+        .ok_or_else(|| anyhow::anyhow!("No pages in document"))?;
+
+    // Transparent background so thumbnails embed cleanly.
+    let pixmap = typst_render::render(frame, scale, Color::from_u8(0, 0, 0, 0));
+    pixmap.encode_png()
+        .map_err(|e| anyhow::anyhow!("Failed to encode PNG: {}", e))
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Node {
     id: String,
@@ -192,59 +840,39 @@ pub struct Node {
     long: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     long_text_svg: Option<String>,
+    #[serde(skip)]
+    path: PathBuf,
 }
 
 impl Node {
     fn parse(file: Option<DirEntry>) -> Option<Self> {
         let file = file?;
         let title_snake_case = file.file_name().to_str()?.to_string();
-        let content = fs::read_to_string(file.path()).ok()?;
+        let path = file.path();
+        let content = fs::read_to_string(&path).ok()?;
         let (short, long) = content
             .split_once("\n")
             .unwrap_or((content.as_str(), ""));
-            
+
         let long_trimmed = long.trim();
-        
-        // Only try to render as Typst if the content looks like Typst code
-        let long_text_svg = if long_trimmed.starts_with('#') || long_trimmed.contains('$') {
-            // Wrap in a document with proper styling
-            let typst_code = format!(
-                r"""
-                #set page(
-                    width: 600pt,
-                    margin: 20pt,
-                    fill: rgb("transparent"),
-                )
-                #set text(
-                    size: 12pt,
-                    fill: rgb(0, 0, 0),
-                )
-                {}
-                """,
-                long_trimmed
-            );
-            
-            match render_typst_to_svg(&typst_code) {
-                Ok(svg) => Some(svg),
-                Err(e) => {
-                    eprintln!("Failed to render Typst to SVG: {}", e);
-                    None
-                }
-            }
-        } else {
-            None
-        };
-        
+
         Some(Node {
             title: title_snake_case.to_case(Case::Title),
             id: title_snake_case,
             short: short.trim().to_string(),
             long: long_trimmed.to_string(),
-            long_text_svg,
+            long_text_svg: None,
+            path,
         })
     }
 
-    fn get_connections(&self) -> Vec<String> {
+    /// Whether the long text looks like Typst code worth rendering, rather
+    /// than plain prose.
+    fn looks_like_typst(&self) -> bool {
+        self.long.starts_with('#') || self.long.contains('$')
+    }
+
+    fn get_connections(&self) -> Vec<(String, EdgeKind)> {
         // TODO: ignore inside raw blocks (only run these regexes on strings ready to display)
         // [id] without (): \[([\w_\s]*)\][^\(]
         // [title](id) block: \[[^\]]*\]\(([\w\s_-]*)\)
@@ -252,13 +880,14 @@ impl Node {
         static ID_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([\w_\s]*)\][^\(]").unwrap());
         static TITLED_ID_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[[^\]]*\]\(([\w\s_-]*)\)").unwrap());
 
-        ID_RE.captures_iter(&self.short)
+        let refs = ID_RE.captures_iter(&self.short)
             .chain(ID_RE.captures_iter(&self.long))
-            .chain(TITLED_ID_RE.captures_iter(&self.short))
+            .map(|e| (e.get(1).expect("1 is non-optional group in regex").as_str().to_string(), EdgeKind::Ref));
+        let titled = TITLED_ID_RE.captures_iter(&self.short)
             .chain(TITLED_ID_RE.captures_iter(&self.long))
-            .map(|e|e.get(1).expect("1 is non-optional group in regex"))
-            .map(|e| e.as_str().to_string())
-            .collect()
+            .map(|e| (e.get(1).expect("1 is non-optional group in regex").as_str().to_string(), EdgeKind::Titled));
+
+        refs.chain(titled).collect()
     }
 }
 
@@ -266,4 +895,120 @@ impl Node {
 pub enum OpenModelError {
     NotADir,
     CantParseNode,
+    CantBuildFonts,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, long: &str) -> Node {
+        Node {
+            title: id.to_case(Case::Title),
+            id: id.to_string(),
+            short: String::new(),
+            long: long.to_string(),
+            long_text_svg: None,
+            path: PathBuf::new(),
+        }
+    }
+
+    fn model_with(nodes: Vec<Node>) -> Model {
+        Model {
+            nodes,
+            root: PathBuf::new(),
+            fonts: FontProvider::new(true),
+            render_cache: RenderCache::default(),
+        }
+    }
+
+    #[test]
+    fn normalize_id_snake_cases_and_trims() {
+        assert_eq!(normalize_id("  My Note  "), "my_note");
+        assert_eq!(normalize_id("my_note"), "my_note");
+    }
+
+    #[test]
+    fn get_edges_resolves_ref_and_titled_links() {
+        let a = node("note_a", "see [note_b] and [Other](note_c)");
+        let b = node("note_b", "");
+        let c = node("note_c", "");
+        let model = model_with(vec![a, b, c]);
+
+        let mut edges = model.get_edges();
+        edges.sort_by(|a, b| a.to.cmp(&b.to));
+
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].from, "note_a");
+        assert_eq!(edges[0].to, "note_b");
+        assert!(matches!(edges[0].kind, EdgeKind::Ref));
+        assert_eq!(edges[1].from, "note_a");
+        assert_eq!(edges[1].to, "note_c");
+        assert!(matches!(edges[1].kind, EdgeKind::Titled));
+    }
+
+    #[test]
+    fn get_edges_drops_dangling_links() {
+        let a = node("note_a", "see [missing_note] here");
+        let model = model_with(vec![a]);
+
+        assert!(model.get_edges().is_empty());
+    }
+
+    #[test]
+    fn get_edges_normalizes_casing_and_spacing_before_matching() {
+        let a = node("note_a", "see [Note B]");
+        let b = node("note_b", "");
+        let model = model_with(vec![a, b]);
+
+        let edges = model.get_edges();
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].to, "note_b");
+    }
+
+    #[test]
+    fn render_cache_key_ignores_scale_for_svg_but_not_png() {
+        let svg_low = RenderCache::key("id", RenderFormat::Svg, 1.0, "text", 0);
+        let svg_high = RenderCache::key("id", RenderFormat::Svg, 2.0, "text", 0);
+        assert_eq!(svg_low, svg_high);
+
+        let png_low = RenderCache::key("id", RenderFormat::Png, 1.0, "text", 0);
+        let png_high = RenderCache::key("id", RenderFormat::Png, 2.0, "text", 0);
+        assert_ne!(png_low, png_high);
+    }
+
+    #[test]
+    fn font_manifest_loads_json_and_toml() {
+        let json = r#"{
+            "families": [
+                { "family": "Body", "fonts": [{ "asset": "body.ttf", "weight": 400 }] }
+            ],
+            "fallback": "Body"
+        }"#;
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("notegraph-test-{}.json", std::process::id()));
+        fs::write(&json_path, json).unwrap();
+        let manifest = FontManifest::load(&json_path).unwrap();
+        fs::remove_file(&json_path).ok();
+        assert_eq!(manifest.families.len(), 1);
+        assert_eq!(manifest.families[0].fonts[0].weight, 400);
+        assert_eq!(manifest.fallback.as_deref(), Some("Body"));
+
+        let toml = r#"
+            fallback = "Body"
+
+            [[families]]
+            family = "Body"
+
+            [[families.fonts]]
+            asset = "body.ttf"
+            weight = 400
+        "#;
+        let toml_path = dir.join(format!("notegraph-test-{}.toml", std::process::id()));
+        fs::write(&toml_path, toml).unwrap();
+        let manifest = FontManifest::load(&toml_path).unwrap();
+        fs::remove_file(&toml_path).ok();
+        assert_eq!(manifest.families[0].family, "Body");
+        assert_eq!(manifest.fallback.as_deref(), Some("Body"));
+    }
 }
\ No newline at end of file
@@ -1,8 +1,29 @@
 use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
 use actix_files::NamedFile;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::models::Model;
+use crate::models::{Model, RenderFormat};
+
+#[derive(Debug, Deserialize)]
+struct RenderQuery {
+    #[serde(default = "default_format")]
+    format: String,
+    #[serde(default = "default_scale")]
+    scale: f32,
+}
+
+fn default_format() -> String {
+    "svg".to_string()
+}
+
+fn default_scale() -> f32 {
+    2.0
+}
+
+/// Bounds on the `scale` query parameter, so a request can't make
+/// `typst_render::render` rasterize an unbounded pixmap.
+const MIN_SCALE: f32 = 0.1;
+const MAX_SCALE: f32 = 10.0;
 
 #[get("/")]
 async fn index() -> impl Responder {
@@ -20,11 +41,39 @@ async fn get_nodes(data: web::Data<Model>) -> impl Responder {
 }
 
 #[get("/api/edges")]
-async fn get_edges() -> impl Responder {
-    // TODO: Implement actual edge retrieval
-    let data: Vec<Vec<String>> = vec![];
+async fn get_edges(data: web::Data<Model>) -> impl Responder {
     HttpResponse::Ok()
-        .json(data)
+        .json(data.get_edges())
+}
+
+#[get("/api/nodes/{id}/backlinks")]
+async fn get_backlinks(data: web::Data<Model>, path: web::Path<String>) -> impl Responder {
+    HttpResponse::Ok()
+        .json(data.get_backlinks(&path.into_inner()))
+}
+
+#[get("/api/nodes/{id}/render")]
+async fn render_node(
+    data: web::Data<Model>,
+    path: web::Path<String>,
+    query: web::Query<RenderQuery>,
+) -> impl Responder {
+    let Some(format) = RenderFormat::parse(&query.format) else {
+        return HttpResponse::BadRequest().body("unknown format");
+    };
+    // `f32::clamp` leaves NaN untouched, so check finiteness before clamping.
+    if !query.scale.is_finite() {
+        return HttpResponse::BadRequest().body("invalid scale");
+    }
+    let scale = query.scale.clamp(MIN_SCALE, MAX_SCALE);
+
+    match data.render_node(&path.into_inner(), format, scale) {
+        Some(Ok((bytes, content_type))) => HttpResponse::Ok()
+            .content_type(content_type)
+            .body(bytes),
+        Some(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        None => HttpResponse::NotFound().finish(),
+    }
 }
 
 pub async fn start_server(data: Model) -> std::io::Result<()> {
@@ -34,6 +83,8 @@ pub async fn start_server(data: Model) -> std::io::Result<()> {
             .service(index)
             .service(get_nodes)
             .service(get_edges)
+            .service(get_backlinks)
+            .service(render_node)
     })
     .bind(("127.0.0.1", 8080))?  // Bind to localhost:8080
     .run()